@@ -1,20 +1,155 @@
 use anyhow::Result;
 use regex::Regex;
 
-#[allow(dead_code)]
+/// Finds the module named `name` in an Android.bp file and returns the full
+/// source text of its `module_type { ... }` definition.
+///
+/// The file is scanned with a brace-balanced parser rather than a regex, so
+/// modules whose body contains nested maps (e.g. `arch: { arm: { ... } }`)
+/// are matched in full instead of being truncated at the first nested `}`.
 pub fn find_module_source<'h>(haystack: &'h str, name: &str) -> Result<Option<&'h str>> {
-    let regex_module = Regex::new(r"(?ms)[ \t]*[_a-zA-Z0-9]+\s*\{.*?^\}")?;
     let regex_name = Regex::new(&format!(r#"(?m)^\s*name:\s*"{}""#, name))?;
-    for cap in regex_module.captures_iter(haystack) {
-        let match_ = cap.get(0).unwrap();
-        if regex_name.is_match(match_.as_str()) {
-            return Ok(Some(&haystack[match_.range()]));
+    for span in top_level_entries(haystack) {
+        if regex_name.is_match(span) {
+            return Ok(Some(span));
         }
     }
-
     Ok(None)
 }
 
+/// Splits a Blueprint file into the source spans of its top-level module
+/// definitions (`module_type { ... }`).
+///
+/// Depth is tracked across `{`, `[` and `(` and their matching close
+/// characters, while string literals (honoring `\"` escapes) and `//` / `/*
+/// */` comments are skipped so that braces inside them don't affect depth.
+fn top_level_entries(haystack: &str) -> Vec<&str> {
+    let bytes = haystack.as_bytes();
+    let mut entries = Vec::new();
+    let mut i = skip_trivia(bytes, 0);
+    while i < bytes.len() {
+        let start = i;
+        let after_ident = match read_ident_end(bytes, i) {
+            Some(end) => end,
+            None => {
+                i = skip_trivia(bytes, i + 1);
+                continue;
+            }
+        };
+        let brace = skip_trivia(bytes, after_ident);
+        if brace >= bytes.len() || bytes[brace] != b'{' {
+            i = skip_trivia(bytes, after_ident);
+            continue;
+        }
+        match find_matching_brace(bytes, brace) {
+            Some(close) => {
+                entries.push(&haystack[start..=close]);
+                i = skip_trivia(bytes, close + 1);
+            }
+            None => break,
+        }
+    }
+    entries
+}
+
+fn is_ident_start(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
+}
+
+fn is_ident_continue(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+fn read_ident_end(bytes: &[u8], i: usize) -> Option<usize> {
+    if i >= bytes.len() || !is_ident_start(bytes[i]) {
+        return None;
+    }
+    let mut j = i + 1;
+    while j < bytes.len() && is_ident_continue(bytes[j]) {
+        j += 1;
+    }
+    Some(j)
+}
+
+/// Skips whitespace and comments starting at `i`, returning the index of the
+/// next significant byte.
+fn skip_trivia(bytes: &[u8], mut i: usize) -> usize {
+    loop {
+        while i < bytes.len() && (bytes[i] as char).is_whitespace() {
+            i += 1;
+        }
+        if bytes[i..].starts_with(b"//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if bytes[i..].starts_with(b"/*") {
+            i += 2;
+            while i < bytes.len() && !bytes[i..].starts_with(b"*/") {
+                i += 1;
+            }
+            i = (i + 2).min(bytes.len());
+            continue;
+        }
+        break;
+    }
+    i
+}
+
+/// Skips a `"..."` string literal starting at `i` (where `bytes[i] == b'"'`),
+/// honoring `\"` escapes, and returns the index just past the closing quote.
+fn skip_string(bytes: &[u8], i: usize) -> usize {
+    let mut j = i + 1;
+    while j < bytes.len() {
+        match bytes[j] {
+            b'\\' => j = (j + 2).min(bytes.len()),
+            b'"' => return j + 1,
+            _ => j += 1,
+        }
+    }
+    j
+}
+
+/// Finds the `}`/`]`/`)` matching the opening bracket at `open`, tracking
+/// nesting depth while skipping over string literals and comments.
+fn find_matching_brace(bytes: &[u8], open: usize) -> Option<usize> {
+    let mut depth = 0i32;
+    let mut i = open;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                i = skip_string(bytes, i);
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !bytes[i..].starts_with(b"*/") {
+                    i += 1;
+                }
+                i = (i + 2).min(bytes.len());
+                continue;
+            }
+            b'{' | b'[' | b'(' => depth += 1,
+            b'}' | b']' | b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -29,4 +164,42 @@ mod tests {
         assert!(source.starts_with("cc_binary {\n    name: \"idmap2\",\n"));
         assert!(source.ends_with("},\n\n}"));
     }
+
+    #[test]
+    fn test_find_module_source_nested_braces() {
+        let blueprint = concat!(
+            "cc_library {\n",
+            "    name: \"before\",\n",
+            "}\n",
+            "\n",
+            "cc_library {\n",
+            "    name: \"libfoo\",\n",
+            "    arch: {\n",
+            "        arm: {\n",
+            "            srcs: [\"arm.cpp\"],\n",
+            "        },\n",
+            "    },\n",
+            "}\n",
+            "\n",
+            "cc_library {\n",
+            "    name: \"after\",\n",
+            "}\n",
+        );
+        let source = find_module_source(blueprint, "libfoo").unwrap().unwrap();
+        assert!(source.contains("arm.cpp"));
+        assert!(source.ends_with("    },\n}"));
+    }
+
+    #[test]
+    fn test_find_module_source_string_with_brace() {
+        let blueprint = concat!(
+            "cc_library {\n",
+            "    name: \"libfoo\",\n",
+            "    cflags: [\"-DFOO=\\\"}\\\"\"],\n",
+            "}\n",
+        );
+        let source = find_module_source(blueprint, "libfoo").unwrap().unwrap();
+        assert!(source.contains("cflags"));
+        assert!(source.ends_with("}"));
+    }
 }